@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::PathBuf;
-use toml;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub struct LoggingConfig {
@@ -12,214 +12,634 @@ pub struct LoggingConfig {
 pub struct Config {
     pub unit_dirs: Vec<PathBuf>,
     pub notification_sockets_dir: PathBuf,
+    /// The config files that were actually loaded, outermost (highest up
+    /// the tree) first, so callers can log which file(s) won.
+    pub config_files: Vec<PathBuf>,
+    /// Where each resolved setting's value ultimately came from, keyed by
+    /// the same dotted paths as the internal settings map.
+    pub origins: HashMap<String, Definition>,
+    /// Non-fatal problems found while validating settings, e.g. a
+    /// configured unit dir that doesn't exist on disk.
+    pub warnings: Vec<String>,
 }
 
-#[derive(Debug)]
+/// Settings an operator can force from the command line. These take
+/// precedence over everything else: defaults, config files and
+/// `RUSTYSD_*` environment variables.
+#[derive(Debug, Default)]
+pub struct ConfigOverride {
+    pub unit_dirs: Option<Vec<PathBuf>>,
+    pub logging_dir: Option<PathBuf>,
+    pub notifications_dir: Option<PathBuf>,
+}
+
+/// Where a resolved setting came from, in increasing order of precedence:
+/// built-in default, a config file, an environment variable, or an
+/// explicit CLI override.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Definition {
+    Default,
+    File(PathBuf),
+    Env,
+    CliOverride,
+}
+
+#[derive(Debug, Clone)]
 enum SettingValue {
     Str(String),
     Array(Vec<SettingValue>),
 }
 
-fn load_toml(
-    config_path: &PathBuf,
-    settings: &mut HashMap<String, SettingValue>,
-) -> Result<(), String> {
-    let toml_conf: toml::Value = match File::open(&config_path) {
-        Ok(mut file) => {
-            let mut config = String::new();
-            use std::io::Read;
-            file.read_to_string(&mut config).unwrap();
-
-            toml::from_str(&config).map_err(|e| format!("Error while decoding config json: {}", e))
-        }
-        Err(e) => Err(format!("Error while opening config file: {}", e)),
-    }?;
+/// A config source format. Parsing always produces a flat map keyed by
+/// dotted setting paths (e.g. `"unit.dirs"`), so formats can be merged
+/// without knowing anything about each other. Each format only has to
+/// know how to deserialize its text into a `RawSettings`; turning that
+/// into the dotted-path map is shared, so adding a new format is a single
+/// `from_str` call rather than another copy of the key extraction.
+trait Format {
+    fn parse(&self, text: &str) -> Result<HashMap<String, SettingValue>, String>;
+}
+
+/// The config keys every format understands, named the way they appear
+/// in the file (`unit_dirs`, not `unit.dirs`).
+#[derive(serde::Deserialize, Default)]
+struct RawSettings {
+    unit_dirs: Option<Vec<String>>,
+    logging_dir: Option<String>,
+    notifications_dir: Option<String>,
+}
 
-    if let toml::Value::Table(map) = &toml_conf {
-        if let Some(toml::Value::Array(elems)) = map.get("unit_dirs") {
+impl From<RawSettings> for HashMap<String, SettingValue> {
+    fn from(raw: RawSettings) -> Self {
+        let mut settings = HashMap::new();
+        if let Some(dirs) = raw.unit_dirs {
             settings.insert(
                 "unit.dirs".to_owned(),
-                SettingValue::Array(
-                    elems
-                        .into_iter()
-                        .map(|e| {
-                            if let toml::Value::String(s) = e {
-                                SettingValue::Str(s.clone())
-                            } else {
-                                SettingValue::Str("".to_owned())
-                            }
-                        })
-                        .collect(),
-                ),
+                SettingValue::Array(dirs.into_iter().map(SettingValue::Str).collect()),
             );
         }
-
-        if let Some(toml::Value::String(val)) = map.get("logging_dir") {
-            settings.insert("logging.dir".to_owned(), SettingValue::Str(val.clone()));
+        if let Some(dir) = raw.logging_dir {
+            settings.insert("logging.dir".to_owned(), SettingValue::Str(dir));
         }
-        if let Some(toml::Value::String(val)) = map.get("notifications_dir") {
-            settings.insert(
-                "notifications.dir".to_owned(),
-                SettingValue::Str(val.clone()),
-            );
+        if let Some(dir) = raw.notifications_dir {
+            settings.insert("notifications.dir".to_owned(), SettingValue::Str(dir));
         }
+        settings
     }
-    Ok(())
 }
 
-fn load_json(
-    config_path: &PathBuf,
-    settings: &mut HashMap<String, SettingValue>,
-) -> Result<(), String> {
-    let json_conf: serde_json::Value = match File::open(config_path) {
-        Ok(mut file) => serde_json::from_reader(&mut file)
-            .map_err(|e| format!("Error while decoding config json: {}", e)),
-        Err(e) => Err(format!("Error while opening config file: {}", e)),
-    }?;
-
-    if let serde_json::Value::Object(map) = &json_conf {
-        if let Some(serde_json::Value::Array(elems)) = map.get("unit_dirs") {
-            settings.insert(
-                "unit.dirs".to_owned(),
-                SettingValue::Array(
-                    elems
-                        .into_iter()
-                        .map(|e| {
-                            if let serde_json::Value::String(s) = e {
-                                SettingValue::Str(s.clone())
-                            } else {
-                                SettingValue::Str("".to_owned())
-                            }
-                        })
-                        .collect(),
-                ),
-            );
-        }
+struct Toml;
+struct Json;
+struct Yaml;
 
-        if let Some(serde_json::Value::String(val)) = map.get("logging_dir") {
-            settings.insert("logging.dir".to_owned(), SettingValue::Str(val.clone()));
+impl Format for Toml {
+    fn parse(&self, text: &str) -> Result<HashMap<String, SettingValue>, String> {
+        let raw: RawSettings =
+            toml::from_str(text).map_err(|e| format!("Error while decoding config toml: {}", e))?;
+        Ok(raw.into())
+    }
+}
+
+impl Format for Json {
+    fn parse(&self, text: &str) -> Result<HashMap<String, SettingValue>, String> {
+        let raw: RawSettings = serde_json::from_str(text)
+            .map_err(|e| format!("Error while decoding config json: {}", e))?;
+        Ok(raw.into())
+    }
+}
+
+impl Format for Yaml {
+    fn parse(&self, text: &str) -> Result<HashMap<String, SettingValue>, String> {
+        let raw: RawSettings = serde_yaml::from_str(text)
+            .map_err(|e| format!("Error while decoding config yaml: {}", e))?;
+        Ok(raw.into())
+    }
+}
+
+/// A config file paired with the `Format` its extension resolved to.
+struct Source {
+    path: PathBuf,
+    format: Box<dyn Format>,
+}
+
+impl Source {
+    /// Builds a `Source` if `path`'s extension maps to a known `Format`.
+    fn detect(path: PathBuf) -> Option<Source> {
+        let format: Box<dyn Format> = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Box::new(Toml),
+            Some("json") => Box::new(Json),
+            Some("yaml") | Some("yml") => Box::new(Yaml),
+            _ => return None,
+        };
+        Some(Source { path, format })
+    }
+
+    fn load(&self) -> Result<HashMap<String, SettingValue>, String> {
+        let mut text = String::new();
+        File::open(&self.path)
+            .map_err(|e| format!("Error while opening config file: {}", e))?
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Error while reading config file: {}", e))?;
+        self.format.parse(&text)
+    }
+}
+
+/// The merged settings alongside the file each key's value was last set by.
+type MergeResult = (HashMap<String, SettingValue>, HashMap<String, Definition>);
+
+/// Parses each source and folds the results left-to-right, so later
+/// sources in `sources` override keys set by earlier ones.
+fn merge_sources(sources: &[Source]) -> Result<MergeResult, String> {
+    let mut settings = HashMap::new();
+    let mut origins = HashMap::new();
+    for source in sources {
+        let parsed = source.load()?;
+        for (key, value) in parsed {
+            origins.insert(key.clone(), Definition::File(source.path.clone()));
+            settings.insert(key, value);
         }
-        if let Some(serde_json::Value::String(val)) = map.get("notifications_dir") {
-            settings.insert(
-                "notifications.dir".to_owned(),
-                SettingValue::Str(val.clone()),
-            );
+    }
+    Ok((settings, origins))
+}
+
+const CONFIG_FILE_NAMES: &[&str] = &[
+    "rustysd_config.toml",
+    "rustysd_config.json",
+    "rustysd_config.yaml",
+    "rustysd_config.yml",
+];
+
+/// Collects the known config file names that actually exist in `dir`, in a
+/// fixed order, as detected `Source`s.
+fn sources_in_dir(dir: &Path) -> Vec<Source> {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.exists())
+        .filter_map(Source::detect)
+        .collect()
+}
+
+/// Looks up a dotted setting path (e.g. `"unit.dirs"`) in the merged
+/// settings map.
+fn get_path<'a>(settings: &'a HashMap<String, SettingValue>, path: &str) -> Option<&'a SettingValue> {
+    settings.get(path)
+}
+
+/// Reads `path` as a single string, erroring if it was set to an array.
+fn as_str<'a>(settings: &'a HashMap<String, SettingValue>, path: &str) -> Result<Option<&'a str>, String> {
+    match get_path(settings, path) {
+        None => Ok(None),
+        Some(SettingValue::Str(s)) => Ok(Some(s)),
+        Some(SettingValue::Array(_)) => Err(format!("expected string for {}, found array", path)),
+    }
+}
+
+/// Reads `path` as a single path, erroring if it was set to an array.
+fn as_path(settings: &HashMap<String, SettingValue>, path: &str) -> Result<Option<PathBuf>, String> {
+    as_str(settings, path).map(|value| value.map(PathBuf::from))
+}
+
+/// Reads `path` as a list of paths. A plain string is treated as a
+/// one-element list, so a single-valued setting can still feed a
+/// `Vec<PathBuf>` consumer.
+fn as_path_vec(settings: &HashMap<String, SettingValue>, path: &str) -> Result<Option<Vec<PathBuf>>, String> {
+    match get_path(settings, path) {
+        None => Ok(None),
+        Some(SettingValue::Str(s)) => Ok(Some(vec![PathBuf::from(s)])),
+        Some(SettingValue::Array(elems)) => {
+            let mut paths = Vec::with_capacity(elems.len());
+            for elem in elems {
+                match elem {
+                    SettingValue::Str(s) => paths.push(PathBuf::from(s)),
+                    SettingValue::Array(_) => {
+                        return Err(format!("expected string elements in {}, found nested array", path))
+                    }
+                }
+            }
+            Ok(Some(paths))
         }
     }
-    Ok(())
 }
 
-pub fn load_config(config_path: Option<&PathBuf>) -> (LoggingConfig, Result<Config, String>) {
-    let mut settings: HashMap<String, SettingValue> = HashMap::new();
+/// Maps a `RUSTYSD_*` environment variable to the dotted setting key and
+/// value it sets. Known keys are matched directly rather than mechanically
+/// turning `_` into `.`, so `notifications_dir` stays one setting instead
+/// of splitting into `notifications.dir`-looking fragments by accident,
+/// and list-valued settings like `unit_dirs` can produce a real
+/// `SettingValue::Array` instead of a single string.
+fn parse_env_var(key: &str, value: &str) -> Option<(String, SettingValue)> {
+    let key = key.strip_prefix("RUSTYSD_")?.to_lowercase();
+    match key.as_str() {
+        "unit_dirs" => Some((
+            "unit.dirs".to_owned(),
+            SettingValue::Array(
+                std::env::split_paths(value)
+                    .map(|path| SettingValue::Str(path.to_string_lossy().into_owned()))
+                    .collect(),
+            ),
+        )),
+        "logging_dir" => Some(("logging.dir".to_owned(), SettingValue::Str(value.to_owned()))),
+        "notifications_dir" => Some((
+            "notifications.dir".to_owned(),
+            SettingValue::Str(value.to_owned()),
+        )),
+        _ => None,
+    }
+}
 
-    let default_config_path_json = PathBuf::from("./config/rustysd_config.json");
-    let default_config_path_toml = PathBuf::from("./config/rustysd_config.toml");
+/// Applies CLI overrides on top of `settings`, recording `Definition::CliOverride`
+/// in `origins` for exactly the keys an override actually sets.
+fn apply_overrides(
+    settings: &mut HashMap<String, SettingValue>,
+    origins: &mut HashMap<String, Definition>,
+    overrides: &ConfigOverride,
+) {
+    if let Some(unit_dirs) = &overrides.unit_dirs {
+        origins.insert("unit.dirs".to_owned(), Definition::CliOverride);
+        settings.insert(
+            "unit.dirs".to_owned(),
+            SettingValue::Array(
+                unit_dirs
+                    .iter()
+                    .map(|dir| SettingValue::Str(dir.to_string_lossy().into_owned()))
+                    .collect(),
+            ),
+        );
+    }
+    if let Some(logging_dir) = &overrides.logging_dir {
+        origins.insert("logging.dir".to_owned(), Definition::CliOverride);
+        settings.insert(
+            "logging.dir".to_owned(),
+            SettingValue::Str(logging_dir.to_string_lossy().into_owned()),
+        );
+    }
+    if let Some(notifications_dir) = &overrides.notifications_dir {
+        origins.insert("notifications.dir".to_owned(), Definition::CliOverride);
+        settings.insert(
+            "notifications.dir".to_owned(),
+            SettingValue::Str(notifications_dir.to_string_lossy().into_owned()),
+        );
+    }
+}
 
-    let config_path_json = if let Some(config_path) = config_path {
-        config_path.join("rustysd_config.json")
-    } else {
-        default_config_path_json.clone()
-    };
+/// Walks from `start` upward toward the filesystem root, collecting every
+/// directory that contains a `rustysd_config.*`. The result is ordered
+/// outermost-first, so a later fold (see `merge_sources`) lets a config
+/// close to `start` override one found further up the tree.
+fn discover_config_dirs(start: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut dir = Some(start.to_path_buf());
+    while let Some(d) = dir {
+        if CONFIG_FILE_NAMES.iter().any(|name| d.join(name).exists()) {
+            found.push(d.clone());
+        }
+        dir = d.parent().map(PathBuf::from);
+    }
+    found.reverse();
+    found
+}
 
-    let config_path_toml = if let Some(config_path) = config_path {
-        config_path.join("rustysd_config.toml")
+/// Resolves the final `Config` by applying, in order, built-in defaults,
+/// config file(s), `RUSTYSD_*` environment variables and finally
+/// `overrides` from the command line. Each later layer only replaces the
+/// keys it actually sets.
+pub fn load_config(
+    config_path: Option<&PathBuf>,
+    overrides: &ConfigOverride,
+) -> (LoggingConfig, Result<Config, String>) {
+    let dirs: Vec<PathBuf> = if let Some(config_path) = config_path {
+        vec![config_path.clone()]
     } else {
-        default_config_path_toml.clone()
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let discovered = discover_config_dirs(&cwd);
+        if discovered.is_empty() {
+            vec![PathBuf::from("./config")]
+        } else {
+            discovered
+        }
     };
 
-    let json_conf = if config_path_json.exists() {
-        Some(load_json(&config_path_json, &mut settings))
-    } else {
-        None
-    };
+    let sources: Vec<Source> = dirs.iter().flat_map(|dir| sources_in_dir(dir)).collect();
 
-    let toml_conf = if config_path_toml.exists() {
-        Some(load_toml(&config_path_toml, &mut settings))
-    } else {
-        None
+    let found_config_file = !sources.is_empty();
+    let config_files: Vec<PathBuf> = sources.iter().map(|source| source.path.clone()).collect();
+
+    let merge_result = merge_sources(&sources);
+
+    let (mut settings, mut origins): MergeResult = match &merge_result {
+        Ok((settings, origins)) => (settings.clone(), origins.clone()),
+        Err(_) => (HashMap::new(), HashMap::new()),
     };
 
     std::env::vars().for_each(|(key, value)| {
-        let mut new_key: Vec<String> = key.split('_').map(|part| part.to_lowercase()).collect();
-        //drop prefix
-        if *new_key[0] == *"rustysd" {
-            new_key.remove(0);
-            let new_key = new_key.join(".");
-            settings.insert(new_key, SettingValue::Str(value.into()));
+        if let Some((setting_key, setting_value)) = parse_env_var(&key, &value) {
+            origins.insert(setting_key.clone(), Definition::Env);
+            settings.insert(setting_key, setting_value);
         }
     });
 
-    let log_dir = settings.get("logging.dir").map(|dir| match dir {
-        SettingValue::Str(s) => Some(PathBuf::from(s)),
-        _ => None,
+    apply_overrides(&mut settings, &mut origins, overrides);
+
+    let mut errors: Vec<String> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
+
+    let log_dir = as_path(&settings, "logging.dir").unwrap_or_else(|e| {
+        errors.push(e);
+        None
     });
 
-    let notification_sockets_dir = settings.get("notifications.dir").map(|dir| match dir {
-        SettingValue::Str(s) => Some(PathBuf::from(s)),
-        _ => None,
+    let notification_sockets_dir = as_path(&settings, "notifications.dir").unwrap_or_else(|e| {
+        errors.push(e);
+        None
     });
 
-    let unit_dirs = settings.get("unit.dirs").map(|dir| match dir {
-        SettingValue::Str(s) => vec![PathBuf::from(s)],
-        SettingValue::Array(arr) => arr
-            .iter()
-            .map(|el| match el {
-                SettingValue::Str(s) => {
-                    println!("s: {}", s);
-                    Some(PathBuf::from(s))
-                }
-                _ => None,
-            })
-            .fold(Vec::new(), |mut acc, el| {
-                if let Some(path) = el {
-                    println!("Got none");
-                    if path.exists() {
-                        acc.push(path)
+    let unit_dirs = as_path_vec(&settings, "unit.dirs")
+        .unwrap_or_else(|e| {
+            errors.push(e);
+            None
+        })
+        .map(|dirs| {
+            dirs.into_iter()
+                .filter(|dir| {
+                    if dir.exists() {
+                        true
+                    } else {
+                        warnings.push(format!("unit dir {:?} configured but missing on disk", dir));
+                        false
                     }
-                }
-                acc
-            }),
-    });
+                })
+                .collect::<Vec<_>>()
+        });
 
-    println!("Settings: {:?}", unit_dirs);
+    // If every configured unit dir was filtered out above, the value
+    // actually in effect is the hardcoded default below, not whatever
+    // file/env/CLI last set "unit.dirs" — keep the recorded origin in
+    // sync with that.
+    let unit_dirs = match unit_dirs {
+        Some(dirs) if dirs.is_empty() => {
+            origins.insert("unit.dirs".to_owned(), Definition::Default);
+            None
+        }
+        other => other,
+    };
+
+    for key in &["logging.dir", "notifications.dir", "unit.dirs"] {
+        origins.entry((*key).to_owned()).or_insert(Definition::Default);
+    }
 
     let config = Config {
         unit_dirs: unit_dirs.unwrap_or_else(|| vec![PathBuf::from("./test_units")]),
 
         notification_sockets_dir: notification_sockets_dir
-            .unwrap_or_else(|| Some(PathBuf::from("./notifications")))
             .unwrap_or_else(|| PathBuf::from("./notifications")),
+
+        config_files,
+        origins,
+        warnings,
     };
 
-    let conf = if let Some(json_conf) = json_conf {
-        if toml_conf.is_some() {
-            Err(format!("Found both json and toml conf!"))
-        } else {
-            match json_conf {
-                Err(e) => Err(e),
-                Ok(_) => Ok(config),
-            }
-        }
-    } else {
-        match toml_conf {
-            Some(Err(e)) => Err(e),
-            Some(Ok(_)) => Ok(config),
-            None => {
-                if *config_path_toml == default_config_path_toml {
-                    Ok(config)
-                } else {
-                    Err("No config file was loaded".into())
-                }
+    let conf = match merge_result {
+        Err(e) => Err(e),
+        Ok(_) => {
+            if !errors.is_empty() {
+                Err(errors.join("; "))
+            } else if found_config_file || config_path.is_none() {
+                Ok(config)
+            } else {
+                Err("No config file was loaded".into())
             }
         }
     };
 
     (
         LoggingConfig {
-            log_dir: log_dir
-                .unwrap_or_else(|| Some(PathBuf::from("./logs")))
-                .unwrap_or_else(|| PathBuf::from("./logs")),
+            log_dir: log_dir.unwrap_or_else(|| PathBuf::from("./logs")),
         },
         conf,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_unit_dirs_with_non_string_element_is_a_hard_error() {
+        let toml = "unit_dirs = [\"a\", 1]\n";
+        let err = Toml.parse(toml).expect_err("non-string array element should fail to parse");
+        assert!(err.contains("Error while decoding config toml"));
+    }
+
+    #[test]
+    fn json_unit_dirs_with_non_string_element_is_a_hard_error() {
+        let json = r#"{"unit_dirs": ["a", 1]}"#;
+        let err = Json.parse(json).expect_err("non-string array element should fail to parse");
+        assert!(err.contains("Error while decoding config json"));
+    }
+
+    #[test]
+    fn parse_env_var_maps_known_keys_without_mangling_underscores() {
+        let (key, value) = parse_env_var("RUSTYSD_NOTIFICATIONS_DIR", "/run/notifications").unwrap();
+        assert_eq!(key, "notifications.dir");
+        match value {
+            SettingValue::Str(s) => assert_eq!(s, "/run/notifications"),
+            SettingValue::Array(_) => panic!("expected a string"),
+        }
+    }
+
+    #[test]
+    fn parse_env_var_splits_unit_dirs_into_an_array() {
+        let (key, value) = parse_env_var("RUSTYSD_UNIT_DIRS", "/a:/b").unwrap();
+        assert_eq!(key, "unit.dirs");
+        match value {
+            SettingValue::Array(elems) => {
+                let strs: Vec<&str> = elems
+                    .iter()
+                    .map(|el| match el {
+                        SettingValue::Str(s) => s.as_str(),
+                        SettingValue::Array(_) => panic!("expected string elements"),
+                    })
+                    .collect();
+                assert_eq!(strs, vec!["/a", "/b"]);
+            }
+            SettingValue::Str(_) => panic!("expected an array"),
+        }
+    }
+
+    #[test]
+    fn parse_env_var_ignores_unrelated_and_unknown_keys() {
+        assert!(parse_env_var("PATH", "/usr/bin").is_none());
+        assert!(parse_env_var("RUSTYSD_SOME_UNKNOWN_KEY", "x").is_none());
+    }
+
+    #[test]
+    fn as_path_vec_treats_single_string_as_one_element_list() {
+        let mut settings = HashMap::new();
+        settings.insert("unit.dirs".to_owned(), SettingValue::Str("/a".to_owned()));
+        let result = as_path_vec(&settings, "unit.dirs").unwrap();
+        assert_eq!(result, Some(vec![PathBuf::from("/a")]));
+    }
+
+    #[test]
+    fn as_path_vec_rejects_nested_arrays() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            "unit.dirs".to_owned(),
+            SettingValue::Array(vec![SettingValue::Array(vec![])]),
+        );
+        assert!(as_path_vec(&settings, "unit.dirs").is_err());
+    }
+
+    #[test]
+    fn as_path_vec_missing_key_is_none() {
+        let settings = HashMap::new();
+        assert_eq!(as_path_vec(&settings, "unit.dirs").unwrap(), None);
+    }
+
+    #[test]
+    fn apply_overrides_wins_over_preexisting_file_and_env_values() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            "logging.dir".to_owned(),
+            SettingValue::Str("/from-file".to_owned()),
+        );
+        let mut origins = HashMap::new();
+        origins.insert(
+            "logging.dir".to_owned(),
+            Definition::File(PathBuf::from("rustysd_config.toml")),
+        );
+
+        let overrides = ConfigOverride {
+            unit_dirs: None,
+            logging_dir: Some(PathBuf::from("/from-cli")),
+            notifications_dir: None,
+        };
+        apply_overrides(&mut settings, &mut origins, &overrides);
+
+        match settings.get("logging.dir").unwrap() {
+            SettingValue::Str(s) => assert_eq!(s, "/from-cli"),
+            SettingValue::Array(_) => panic!("expected a string"),
+        }
+        assert_eq!(origins.get("logging.dir"), Some(&Definition::CliOverride));
+    }
+
+    #[test]
+    fn apply_overrides_only_touches_origins_for_keys_it_actually_sets() {
+        let mut settings = HashMap::new();
+        let mut origins = HashMap::new();
+        origins.insert("notifications.dir".to_owned(), Definition::Env);
+
+        let overrides = ConfigOverride {
+            unit_dirs: Some(vec![PathBuf::from("/units")]),
+            logging_dir: None,
+            notifications_dir: None,
+        };
+        apply_overrides(&mut settings, &mut origins, &overrides);
+
+        assert_eq!(origins.get("unit.dirs"), Some(&Definition::CliOverride));
+        assert_eq!(origins.get("logging.dir"), None);
+        assert_eq!(origins.get("notifications.dir"), Some(&Definition::Env));
+    }
+
+    #[test]
+    fn discover_config_dirs_walks_up_and_orders_outermost_first() {
+        let root = std::env::temp_dir().join(format!(
+            "rustysd_config_test_{}_discover_config_dirs",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let a = root.join("a");
+        let a_b = a.join("b");
+        let a_b_c = a_b.join("c");
+        std::fs::create_dir_all(&a_b_c).unwrap();
+
+        std::fs::write(a.join("rustysd_config.toml"), "").unwrap();
+        std::fs::write(a_b_c.join("rustysd_config.toml"), "").unwrap();
+
+        let found = discover_config_dirs(&a_b_c);
+        assert_eq!(found, vec![a.clone(), a_b_c.clone()]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn discover_config_dirs_finds_nothing_without_any_config_file() {
+        let root = std::env::temp_dir().join(format!(
+            "rustysd_config_test_{}_discover_config_dirs_empty",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let nested = root.join("x").join("y");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(discover_config_dirs(&nested), Vec::<PathBuf>::new());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn missing_unit_dirs_fall_back_to_default_origin_once_filtered_out() {
+        let dir = std::env::temp_dir().join(format!(
+            "rustysd_config_test_{}_missing_unit_dirs_origin",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("rustysd_config.toml"),
+            "unit_dirs = [\"/does/not/exist\"]\n",
+        )
+        .unwrap();
+
+        let (_logging, conf) = load_config(Some(&dir), &ConfigOverride::default());
+        let conf = conf.unwrap();
+
+        assert_eq!(conf.unit_dirs, vec![PathBuf::from("./test_units")]);
+        assert_eq!(conf.origins.get("unit.dirs"), Some(&Definition::Default));
+        assert!(conf
+            .warnings
+            .iter()
+            .any(|w| w.contains("configured but missing on disk")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merge_sources_lets_later_sources_override_earlier_ones() {
+        let dir = std::env::temp_dir().join(format!(
+            "rustysd_config_test_{}_{}",
+            std::process::id(),
+            "merge_sources_precedence"
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml_path = dir.join("a.toml");
+        let json_path = dir.join("b.json");
+        std::fs::write(
+            &toml_path,
+            "logging_dir = \"/from-toml\"\nnotifications_dir = \"/notif\"\n",
+        )
+        .unwrap();
+        std::fs::write(&json_path, r#"{"logging_dir": "/from-json"}"#).unwrap();
+
+        let sources = vec![
+            Source::detect(toml_path.clone()).unwrap(),
+            Source::detect(json_path.clone()).unwrap(),
+        ];
+        let (settings, origins) = merge_sources(&sources).unwrap();
+
+        match settings.get("logging.dir").unwrap() {
+            SettingValue::Str(s) => assert_eq!(s, "/from-json"),
+            SettingValue::Array(_) => panic!("expected a string"),
+        }
+        match origins.get("logging.dir").unwrap() {
+            Definition::File(p) => assert_eq!(p, &json_path),
+            other => panic!("expected a File origin, got {:?}", other),
+        }
+        // notifications_dir was only ever set by the first source, so it
+        // survives the merge untouched.
+        assert!(settings.contains_key("notifications.dir"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}